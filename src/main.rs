@@ -1,7 +1,39 @@
-use std::{error::Error, ffi::OsStr, io, path::Path, process};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    error::Error,
+    ffi::OsStr,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process,
+};
 
-use clap::Parser;
-use image::{imageops, open, GenericImageView, ImageBuffer};
+use clap::{Parser, ValueEnum};
+use image::{imageops, open, DynamicImage, GenericImage, GenericImageView, ImageBuffer, Luma, Rgba};
+use rayon::prelude::*;
+use serde::Serialize;
+
+type Tile = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+/// An RGB color, used by the palette quantizer (alpha is left untouched by quantization)
+type Rgb = [u8; 3];
+
+/// Hashes a tile's raw pixel bytes for O(1) average dedup lookups. Callers must still confirm
+/// an exact byte match on hash collision.
+fn tile_hash(tile: &Tile) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tile.as_raw().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Horizontal/vertical flip flag bits recorded per tilemap cell when `--dedupe-flips` is on.
+/// bit0 = horizontal flip, bit1 = vertical flip, bit2 = reserved for 90° rotation on square tiles
+const HFLIP: u8 = 0b001;
+const VFLIP: u8 = 0b010;
+
+/// Tile size used when the user doesn't pass `--size`
+const DEFAULT_TILE_SIZE: u32 = 8;
 
 /// Creates sick tilesets out of png images
 #[derive(Parser, Debug)]
@@ -10,23 +42,401 @@ use image::{imageops, open, GenericImageView, ImageBuffer};
 #[command(version = "1.0")]
 #[command(about = "Converts png images (tilemaps) into png tilesets", long_about = None)]
 struct Args {
-    /// Output file path
+    /// Output file path (single mode) or directory (required in `--mode all`, so the batch
+    /// doesn't rescan and reprocess its own prior output)
     #[arg(short, long)]
     output: Option<String>,
 
-    /// Tile size (in pixels)
-    #[arg(short, long, default_value_t = 8)]
-    size: u32,
+    /// Tile size (in pixels). Defaults to 8, or to the Aseprite file's declared tile grid for
+    /// `.ase`/`.aseprite` input when not given explicitly
+    #[arg(short, long)]
+    size: Option<u32>,
+
+    /// Tilemap output path. Written as CSV if the extension is `.csv`, JSON otherwise
+    #[arg(short, long)]
+    map: Option<String>,
+
+    /// Deduplicate tiles under horizontal/vertical flips and 180° rotation, recording the
+    /// transform needed to reconstruct each cell in the tilemap
+    #[arg(long)]
+    dedupe_flips: bool,
 
-    /// File path
+    /// Conversion mode: `single` converts one file, `all` treats `file` as a directory of PNGs
+    #[arg(long, value_enum, default_value_t = Mode::Single)]
+    mode: Mode,
+
+    /// Quantize tile colors to a shared N-color palette before deduplication (median-cut)
+    #[arg(long)]
+    palette: Option<u32>,
+
+    /// Also write an indexed tileset (one palette index byte per pixel) alongside the RGBA one.
+    /// Requires `--palette`
+    #[arg(long)]
+    indexed: bool,
+
+    /// For `.ase`/`.aseprite` input that already has a tileset, read its tiles and tilemap cel
+    /// directly instead of re-slicing the composited frame
+    #[arg(long)]
+    passthrough_tileset: bool,
+
+    /// File path (single mode) or source directory (all mode)
     file: String,
 }
 
-fn run(args: &Args) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(&args.file);
-    let image = open(&args.file)?;
-    let tile_size = args.size;
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum Mode {
+    Single,
+    All,
+}
+
+/// Packs `tiles` into the smallest roughly-square sheet whose dimensions are multiples of
+/// `tile_size`, left-to-right then top-to-bottom.
+fn pack_tiles(tiles: &[Tile], tile_size: u32) -> Tile {
+    let pixels = tiles.len() as u32 * tile_size;
+
+    let (mut width, mut height) = (pixels, tile_size);
+
+    for height_pretender in 0..pixels {
+        for width_pretender in 0..pixels {
+            if height_pretender * width_pretender >= pixels * tile_size
+                && height_pretender % tile_size == 0
+                && width_pretender % tile_size == 0
+                && width_pretender + height_pretender < width + height
+            {
+                width = width_pretender;
+                height = height_pretender;
+            }
+        }
+    }
+
+    let mut result_image = ImageBuffer::new(width, height);
+    let mut y = 0;
+    let mut x = 0;
+    for tile in tiles {
+        if x == width {
+            y += tile_size;
+            x = 0;
+        }
+        imageops::overlay(&mut result_image, tile, x as i64, y as i64);
+        x += tile_size;
+    }
+
+    result_image
+}
+
+/// Picks the canonical form of a tile under horizontal flip, vertical flip, and 180° rotation
+/// (the variant whose raw bytes sort lexicographically smallest), returning it alongside the
+/// flag describing the transform that was applied to `tile` to reach it.
+fn canonical_tile(tile: &Tile) -> (Tile, u8) {
+    let candidates = [
+        (tile.clone(), 0),
+        (imageops::flip_horizontal(tile), HFLIP),
+        (imageops::flip_vertical(tile), VFLIP),
+        (imageops::rotate180(tile), HFLIP | VFLIP),
+    ];
+
+    candidates
+        .into_iter()
+        .min_by(|(a, _), (b, _)| a.as_raw().cmp(b.as_raw()))
+        .unwrap()
+}
+
+/// Collects every distinct RGB color in `image`, in first-seen order, ignoring alpha.
+fn collect_colors(image: &DynamicImage) -> Vec<Rgb> {
+    let mut seen = HashSet::new();
+    let mut colors = vec![];
+
+    for (_, _, pixel) in image.pixels() {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        if seen.insert(rgb) {
+            colors.push(rgb);
+        }
+    }
+
+    colors
+}
+
+/// Returns the axis (0=R, 1=G, 2=B) with the widest range across `colors`, and that range.
+fn longest_axis(colors: &[Rgb]) -> (usize, u8) {
+    let mut mins = [u8::MAX; 3];
+    let mut maxs = [0u8; 3];
+
+    for color in colors {
+        for channel in 0..3 {
+            mins[channel] = mins[channel].min(color[channel]);
+            maxs[channel] = maxs[channel].max(color[channel]);
+        }
+    }
+
+    (0..3)
+        .map(|channel| (channel, maxs[channel] - mins[channel]))
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn average_color(colors: &[Rgb]) -> Rgb {
+    let mut sums = [0u32; 3];
+
+    for color in colors {
+        for channel in 0..3 {
+            sums[channel] += color[channel] as u32;
+        }
+    }
+
+    let count = colors.len() as u32;
+    [
+        (sums[0] / count) as u8,
+        (sums[1] / count) as u8,
+        (sums[2] / count) as u8,
+    ]
+}
+
+/// Reduces `colors` to at most `n` representative colors via median-cut: repeatedly split the
+/// box with the largest range along its longest RGB axis at the median, until there are `n`
+/// boxes, then average each box to get its palette entry.
+fn median_cut(colors: Vec<Rgb>, n: u32) -> Vec<Rgb> {
+    let mut boxes = vec![colors];
+
+    while (boxes.len() as u32) < n {
+        let splittable_index = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .max_by_key(|(_, colors)| longest_axis(colors).1)
+            .map(|(index, _)| index);
+
+        let Some(index) = splittable_index else {
+            break;
+        };
+
+        let mut box_colors = boxes.remove(index);
+        let (axis, _) = longest_axis(&box_colors);
+        box_colors.sort_by_key(|color| color[axis]);
+
+        let second_half = box_colors.split_off(box_colors.len() / 2);
+        boxes.push(box_colors);
+        boxes.push(second_half);
+    }
+
+    boxes.iter().map(|b| average_color(b)).collect()
+}
+
+/// Builds an N-color palette for `image` via median-cut, or returns the image's own colors
+/// unchanged (with a warning) if it already has N colors or fewer.
+fn build_palette(image: &DynamicImage, n: u32) -> Vec<Rgb> {
+    let colors = collect_colors(image);
+
+    if colors.len() as u32 <= n {
+        eprintln!(
+            "WARNING: source image already has {} color(s), which is <= the requested palette size of {}; skipping quantization",
+            colors.len(),
+            n
+        );
+        return colors;
+    }
 
+    median_cut(colors, n)
+}
+
+fn nearest_palette_color(color: Rgb, palette: &[Rgb]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| {
+            let dr = color[0] as i32 - candidate[0] as i32;
+            let dg = color[1] as i32 - candidate[1] as i32;
+            let db = color[2] as i32 - candidate[2] as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .map(|(index, _)| index)
+        .unwrap()
+}
+
+/// Remaps every pixel of `image` to its nearest color in `palette` (alpha untouched).
+fn quantize_image(image: &mut DynamicImage, palette: &[Rgb]) {
+    let (width, height) = image.dimensions();
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut pixel = image.get_pixel(x, y);
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            let nearest = palette[nearest_palette_color(rgb, palette)];
+            pixel[0] = nearest[0];
+            pixel[1] = nearest[1];
+            pixel[2] = nearest[2];
+            image.put_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Palette serialized to JSON alongside an indexed tileset
+#[derive(Serialize)]
+struct PaletteJson {
+    colors: Vec<Rgb>,
+}
+
+fn write_palette(palette_path: &Path, palette: &[Rgb]) -> Result<(), Box<dyn Error>> {
+    let extension = palette_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+
+    if extension == "pal" {
+        let bytes: Vec<u8> = palette.iter().flatten().copied().collect();
+        fs::write(palette_path, bytes)?;
+    } else {
+        let json = PaletteJson {
+            colors: palette.to_vec(),
+        };
+        fs::write(palette_path, serde_json::to_string_pretty(&json)?)?;
+    }
+
+    Ok(())
+}
+
+/// Builds `<output_path's stem>-<suffix>.<extension>` next to `output_path`
+fn sibling_path(output_path: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or("my");
+    let dir = output_path.parent().unwrap_or(Path::new(""));
+
+    dir.join(format!("{}-{}.{}", stem, suffix, extension))
+}
+
+/// Tilemap serialized to JSON: a grid of indices into the deduplicated tileset, plus an
+/// optional grid of flip flags when `--dedupe-flips` is enabled
+#[derive(Serialize)]
+struct TilemapJson {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    tiles: Vec<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flips: Option<Vec<Vec<u8>>>,
+}
+
+fn write_tilemap(
+    map_path: &Path,
+    tilemap: &[Vec<u32>],
+    flipmap: Option<&[Vec<u8>]>,
+    tile_size: u32,
+) -> Result<(), Box<dyn Error>> {
+    let extension = map_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+
+    if extension == "csv" {
+        let csv = tilemap
+            .iter()
+            .enumerate()
+            .map(|(row_index, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(|(col_index, index)| match flipmap {
+                        Some(flipmap) => format!("{}:{}", index, flipmap[row_index][col_index]),
+                        None => index.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        fs::write(map_path, csv)?;
+    } else {
+        let height = tilemap.len() as u32;
+        let width = tilemap.first().map(|row| row.len()).unwrap_or(0) as u32;
+        let json = TilemapJson {
+            width,
+            height,
+            tile_size,
+            tiles: tilemap.to_vec(),
+            flips: flipmap.map(|flipmap| flipmap.to_vec()),
+        };
+
+        fs::write(map_path, serde_json::to_string_pretty(&json)?)?;
+    }
+
+    Ok(())
+}
+
+/// Decodes a `.ase`/`.aseprite` file's first frame into a flattened RGBA image, and resolves the
+/// tile size to use: `requested_size` (the explicit `--size`, if the caller passed one) wins,
+/// otherwise the file's own declared tile grid wins, otherwise `DEFAULT_TILE_SIZE`.
+fn open_aseprite_image(
+    input_path: &Path,
+    requested_size: Option<u32>,
+) -> Result<(DynamicImage, u32), Box<dyn Error>> {
+    let ase = asefile::AsepriteFile::read_file(input_path)?;
+
+    let tile_size = match requested_size {
+        Some(size) => size,
+        None => ase
+            .tilesets()
+            .iter()
+            .next()
+            .map(|tileset| {
+                let grid_size = tileset.tile_size();
+                grid_size.width().max(grid_size.height()) as u32
+            })
+            .unwrap_or(DEFAULT_TILE_SIZE),
+    };
+
+    Ok((DynamicImage::ImageRgba8(ase.frame(0).image()), tile_size))
+}
+
+/// Reads tiles and the tilemap directly from an Aseprite file's own tileset and tile layer,
+/// skipping the composite-then-reslice path. Assumes the file has a tileset and a tilemap layer.
+fn convert_ase_passthrough(
+    input_path: &Path,
+    output_path: &Path,
+    map_path: Option<&Path>,
+) -> Result<usize, Box<dyn Error>> {
+    let ase = asefile::AsepriteFile::read_file(input_path)?;
+
+    let tileset = ase.tilesets().iter().next().ok_or_else(|| {
+        Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "ERROR: Aseprite file has no tileset to pass through",
+        )) as Box<dyn Error>
+    })?;
+
+    let tile_size = tileset.tile_size().width() as u32;
+    let tiles: Vec<Tile> = (0..tileset.tile_count())
+        .map(|tile_id| tileset.tile_image(tile_id))
+        .collect();
+
+    let result_image = pack_tiles(&tiles, tile_size);
+    result_image.save(output_path)?;
+
+    if let Some(map_path) = map_path {
+        let tilemap_layer_id = ase.layers().find(|layer| layer.is_tilemap()).map(|layer| layer.id());
+
+        if let Some(tilemap) = tilemap_layer_id.and_then(|layer_id| ase.tilemap(layer_id, 0)) {
+            let tilemap_grid: Vec<Vec<u32>> = (0..tilemap.height())
+                .map(|y| (0..tilemap.width()).map(|x| tilemap.tile(x, y).id()).collect())
+                .collect();
+
+            write_tilemap(map_path, &tilemap_grid, None, tile_size)?;
+        }
+    }
+
+    Ok(tiles.len())
+}
+
+/// Converts a single PNG tilemap into a deduplicated tileset, returning the number of unique
+/// tiles found. `map_path`, when given, also gets a tilemap written alongside the tileset.
+fn convert_file(
+    input_path: &Path,
+    output_path: &Path,
+    map_path: Option<&Path>,
+    args: &Args,
+) -> Result<usize, Box<dyn Error>> {
     let make_error = |text| {
         Box::new(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -34,6 +444,33 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
         ))
     };
 
+    if args.indexed && args.palette.is_none() {
+        return Err(make_error("--indexed requires --palette"));
+    }
+
+    let extension = input_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+    let is_aseprite = extension == "ase" || extension == "aseprite";
+
+    if is_aseprite && args.passthrough_tileset {
+        if args.palette.is_some() || args.dedupe_flips {
+            return Err(make_error(
+                "--palette and --dedupe-flips are not supported together with --passthrough-tileset, since it reads the file's own tiles and tilemap as-is",
+            ));
+        }
+
+        return convert_ase_passthrough(input_path, output_path, map_path);
+    }
+
+    let (mut image, tile_size) = if is_aseprite {
+        open_aseprite_image(input_path, args.size)?
+    } else {
+        (open(input_path)?, args.size.unwrap_or(DEFAULT_TILE_SIZE))
+    };
+
     if image.width() % tile_size != 0 {
         return Err(make_error(
             "Image width must be a multiple of the tile size",
@@ -46,13 +483,23 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
         ));
     }
 
+    let palette = args.palette.map(|n| build_palette(&image, n));
+    if let Some(palette) = &palette {
+        quantize_image(&mut image, palette);
+    }
+
     let x_tiles_count = image.width() / tile_size;
     let y_tiles_count = image.height() / tile_size;
 
-    let mut res = vec![];
+    let grid: Vec<(u32, u32)> = (0..y_tiles_count)
+        .flat_map(|y_tile| (0..x_tiles_count).map(move |x_tile| (y_tile, x_tile)))
+        .collect();
 
-    for y_tile in 0..y_tiles_count {
-        for x_tile in 0..x_tiles_count {
+    // Extraction and canonicalization are independent per cell, so run them across threads;
+    // the dedup merge below stays sequential to keep tile indices deterministic.
+    let extracted: Vec<(Tile, u8)> = grid
+        .par_iter()
+        .map(|&(y_tile, x_tile)| {
             let mut inner = ImageBuffer::new(tile_size, tile_size);
 
             for x in 0..tile_size {
@@ -65,51 +512,168 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
                 }
             }
 
-            if !res.contains(&inner) {
-                res.push(inner);
+            if args.dedupe_flips {
+                canonical_tile(&inner)
+            } else {
+                (inner, 0)
             }
+        })
+        .collect();
+
+    let mut res: Vec<Tile> = vec![];
+    let mut tilemap = vec![];
+    let mut flipmap = vec![];
+    let mut hash_index: HashMap<u64, Vec<u32>> = HashMap::new();
+    let mut extracted = extracted.into_iter();
+
+    for _ in 0..y_tiles_count {
+        let mut tilemap_row = vec![];
+        let mut flipmap_row = vec![];
+
+        for _ in 0..x_tiles_count {
+            let (key_tile, flip) = extracted.next().unwrap();
+            let hash = tile_hash(&key_tile);
+
+            let existing_index = hash_index
+                .get(&hash)
+                .and_then(|indices| indices.iter().find(|&&index| res[index as usize] == key_tile))
+                .copied();
+
+            let tile_index = match existing_index {
+                Some(index) => index,
+                None => {
+                    let index = res.len() as u32;
+                    res.push(key_tile);
+                    hash_index.entry(hash).or_default().push(index);
+                    index
+                }
+            };
+
+            tilemap_row.push(tile_index);
+            flipmap_row.push(flip);
         }
+
+        tilemap.push(tilemap_row);
+        flipmap.push(flipmap_row);
     }
-    let pixels = res.len() as u32 * tile_size;
 
-    let (mut width, mut height) = (pixels, tile_size);
+    if let Some(map_path) = map_path {
+        let flips = args.dedupe_flips.then_some(flipmap.as_slice());
+        write_tilemap(map_path, &tilemap, flips, tile_size)?;
+    }
 
-    for height_pretender in 0..pixels {
-        for width_pretender in 0..pixels {
-            if height_pretender * width_pretender >= pixels * tile_size
-                && height_pretender % tile_size == 0
-                && width_pretender % tile_size == 0
-                && width_pretender + height_pretender < width + height
-            {
-                width = width_pretender;
-                height = height_pretender;
+    let result_image = pack_tiles(&res, tile_size);
+    result_image.save(output_path)?;
+
+    if let Some(palette) = &palette {
+        write_palette(&sibling_path(output_path, "palette", "json"), palette)?;
+
+        if args.indexed {
+            let (width, height) = result_image.dimensions();
+            let mut indexed_image: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::new(width, height);
+
+            for (x, y, pixel) in result_image.enumerate_pixels() {
+                let rgb = [pixel[0], pixel[1], pixel[2]];
+                let index = nearest_palette_color(rgb, palette) as u8;
+                indexed_image.put_pixel(x, y, Luma([index]));
             }
+
+            indexed_image.save(sibling_path(output_path, "indexed", "png"))?;
         }
     }
 
-    let mut result_image = ImageBuffer::new(width, height);
-    let mut y = 0;
-    let mut x = 0;
-    for tile in res.iter() {
-        if x == width {
-            y += tile_size;
-            x = 0;
+    Ok(res.len())
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    match args.mode {
+        Mode::Single => {
+            let input_path = Path::new(&args.file);
+            let size = args.size.unwrap_or(DEFAULT_TILE_SIZE);
+            let default_file_path = format!(
+                "{}-tileset-{}x{}.png",
+                input_path
+                    .file_stem()
+                    .unwrap_or(OsStr::new("my"))
+                    .to_str()
+                    .unwrap_or("my"),
+                size,
+                size
+            );
+            let output_path = args
+                .output
+                .as_ref()
+                .map(Path::new)
+                .unwrap_or(Path::new(&default_file_path));
+            let map_path = args.map.as_ref().map(Path::new);
+
+            convert_file(input_path, output_path, map_path, args)?;
+
+            Ok(())
         }
-        imageops::overlay(&mut result_image, tile, x as i64, y as i64);
-        x += tile_size;
+        Mode::All => run_batch(args),
     }
+}
+
+fn run_batch(args: &Args) -> Result<(), Box<dyn Error>> {
+    let output_dir = args.output.as_ref().map(Path::new).ok_or_else(|| {
+        Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "ERROR: --output is required in --mode all, so the batch doesn't reprocess its own output on rerun",
+        )) as Box<dyn Error>
+    })?;
+    let source_dir = Path::new(&args.file);
+    let size = args.size.unwrap_or(DEFAULT_TILE_SIZE);
+
+    fs::create_dir_all(output_dir)?;
 
-    let default_file_path = format!(
-        "{}-tileset-{}x{}.png",
-        path.file_stem()
+    let mut entries: Vec<_> = fs::read_dir(source_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(OsStr::to_str)
+                .map(|ext| ext.eq_ignore_ascii_case("png"))
+                .unwrap_or(false)
+        })
+        .collect();
+    entries.sort();
+
+    let mut processed = 0;
+    let mut skipped = 0;
+    let mut total_unique_tiles = 0;
+
+    for input_path in entries {
+        let stem = input_path
+            .file_stem()
             .unwrap_or(OsStr::new("my"))
             .to_str()
-            .unwrap_or("my"),
-        tile_size,
-        tile_size
+            .unwrap_or("my");
+        let output_path = output_dir.join(format!("{}-tileset-{}x{}.png", stem, size, size));
+        let map_path = args.map.as_ref().map(|map| {
+            let extension = Path::new(map)
+                .extension()
+                .and_then(OsStr::to_str)
+                .unwrap_or("json");
+            output_dir.join(format!("{}-tilemap.{}", stem, extension))
+        });
+
+        match convert_file(&input_path, &output_path, map_path.as_deref(), args) {
+            Ok(unique_tiles) => {
+                processed += 1;
+                total_unique_tiles += unique_tiles;
+            }
+            Err(err) => {
+                skipped += 1;
+                eprintln!("SKIPPED {}: {}", input_path.display(), err);
+            }
+        }
+    }
+
+    println!(
+        "Processed {} file(s), skipped {} file(s), {} unique tile(s) total",
+        processed, skipped, total_unique_tiles
     );
-    let output_path = args.output.as_ref().unwrap_or(&default_file_path);
-    result_image.save(output_path)?;
 
     Ok(())
 }
@@ -129,9 +693,9 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use image::open;
+    use image::{open, GenericImageView};
 
-    use crate::{run, Args};
+    use crate::{run, Args, Mode};
     use std::env;
     use std::fs::remove_file;
     use std::sync::Once;
@@ -150,7 +714,13 @@ mod tests {
 
         let args = Args {
             output: None,
-            size: 8,
+            size: Some(8),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("1.png"),
         };
 
@@ -163,7 +733,13 @@ mod tests {
 
         let args = Args {
             output: None,
-            size: 8,
+            size: Some(8),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("2.png"),
         };
 
@@ -176,7 +752,13 @@ mod tests {
 
         let args = Args {
             output: None,
-            size: 16,
+            size: Some(16),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("3.png"),
         };
 
@@ -197,7 +779,13 @@ mod tests {
 
         let args = Args {
             output: None,
-            size: 8,
+            size: Some(8),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("3.png"),
         };
 
@@ -218,7 +806,13 @@ mod tests {
 
         let args = Args {
             output: None,
-            size: 4,
+            size: Some(4),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("3.png"),
         };
 
@@ -240,7 +834,13 @@ mod tests {
         let output_name = "custom-tileset-name.png";
         let args = Args {
             output: Some(String::from(output_name)),
-            size: 4,
+            size: Some(4),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
             file: String::from("3.png"),
         };
 
@@ -252,4 +852,284 @@ mod tests {
 
         assert!(remove_file(output_name).is_ok());
     }
+
+    #[test]
+    fn returns_ok_and_writes_json_tilemap_alongside_tileset() {
+        setup();
+
+        // 7.png is 8x8: top-left and top-right 4x4 quadrants are an identical solid red, so they
+        // must dedupe to the same tile index; bottom-left (blue) and bottom-right (green) are
+        // each unique.
+        let output_name = "json-tilemap-output.png";
+        let map_name = "7-tilemap.json";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: Some(4),
+            map: Some(String::from(map_name)),
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("7.png"),
+        };
+
+        assert!(run(&args).is_ok());
+
+        let map_contents = std::fs::read_to_string(map_name).unwrap();
+        let tilemap: serde_json::Value = serde_json::from_str(&map_contents).unwrap();
+        assert_eq!(tilemap["width"], 2);
+        assert_eq!(tilemap["height"], 2);
+        assert_eq!(tilemap["tile_size"], 4);
+        assert_eq!(tilemap["tiles"], serde_json::json!([[0, 0], [1, 2]]));
+
+        assert!(remove_file(output_name).is_ok());
+        assert!(remove_file(map_name).is_ok());
+    }
+
+    #[test]
+    fn returns_ok_and_writes_flip_flags_when_dedupe_flips_is_enabled() {
+        setup();
+
+        // 8.png is 8x4: its right 4x4 tile is the exact horizontal mirror of its left tile, so
+        // with --dedupe-flips both cells must collapse to tile 0, the left (unflipped) one
+        // recorded as flag 0 and the right (mirrored) one as flag 1 (HFLIP).
+        let output_name = "flip-flags-output.png";
+        let map_name = "8-tilemap-flips.json";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: Some(4),
+            map: Some(String::from(map_name)),
+            dedupe_flips: true,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("8.png"),
+        };
+
+        assert!(run(&args).is_ok());
+
+        let map_contents = std::fs::read_to_string(map_name).unwrap();
+        let tilemap: serde_json::Value = serde_json::from_str(&map_contents).unwrap();
+        assert_eq!(tilemap["tiles"], serde_json::json!([[0, 0]]));
+        assert_eq!(tilemap["flips"], serde_json::json!([[0, 1]]));
+
+        assert!(remove_file(output_name).is_ok());
+        assert!(remove_file(map_name).is_ok());
+    }
+
+    #[test]
+    fn returns_ok_and_converts_every_png_in_a_directory_in_all_mode() {
+        setup();
+
+        let output_dir = "batch-output";
+        let args = Args {
+            output: Some(String::from(output_dir)),
+            size: Some(16),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::All,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("."),
+        };
+
+        assert!(run(&args).is_ok());
+        assert!(open(format!("{}/3-tileset-16x16.png", output_dir)).is_ok());
+
+        assert!(std::fs::remove_dir_all(output_dir).is_ok());
+    }
+
+    #[test]
+    fn returns_err_when_mode_all_is_used_without_output() {
+        setup();
+
+        let args = Args {
+            output: None,
+            size: Some(16),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::All,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("."),
+        };
+
+        assert!(run(&args).is_err());
+    }
+
+    #[test]
+    fn returns_ok_and_writes_palette_and_indexed_tileset() {
+        setup();
+
+        // 9.png has 4 distinct solid quadrant colors (red, green, blue, yellow) quantized down to
+        // 2: median-cut splits on the blue axis into {red, green, yellow} vs {blue}, whose
+        // averages are (127,127,0) and (127,127,127).
+        let output_name = "palette-indexed-output.png";
+        let palette_name = "palette-indexed-output-palette.json";
+        let indexed_name = "palette-indexed-output-indexed.png";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: Some(4),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: Some(2),
+            indexed: true,
+            passthrough_tileset: false,
+            file: String::from("9.png"),
+        };
+
+        assert!(run(&args).is_ok());
+
+        let palette_contents = std::fs::read_to_string(palette_name).unwrap();
+        let palette: serde_json::Value = serde_json::from_str(&palette_contents).unwrap();
+        assert_eq!(
+            palette["colors"],
+            serde_json::json!([[127, 127, 0], [127, 127, 127]])
+        );
+
+        // Quantized quadrants: red and green are nearest (127,127,0) (index 0), blue is nearest
+        // (127,127,127) (index 1), yellow is nearest (127,127,0) (index 0) again.
+        let indexed_image = open(indexed_name).unwrap();
+        let expected_indices = [[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 0, 0], [1, 1, 0, 0]];
+        for (y, row) in expected_indices.iter().enumerate() {
+            for (x, &expected) in row.iter().enumerate() {
+                assert_eq!(indexed_image.get_pixel(x as u32, y as u32)[0], expected);
+            }
+        }
+
+        assert!(remove_file(output_name).is_ok());
+        assert!(remove_file(palette_name).is_ok());
+        assert!(remove_file(indexed_name).is_ok());
+    }
+
+    #[test]
+    fn returns_ok_and_uses_aseprite_declared_tile_grid_when_size_is_not_given() {
+        setup();
+
+        // 4.aseprite declares a 16x16 tileset and has no --size override, so the tileset
+        // should come out at that size instead of DEFAULT_TILE_SIZE.
+        let output_name = "aseprite-default-size-output.png";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: None,
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("4.aseprite"),
+        };
+
+        assert!(run(&args).is_ok());
+        assert_eq!(open(output_name).unwrap().width() % 16, 0);
+
+        assert!(remove_file(output_name).is_ok());
+    }
+
+    #[test]
+    fn returns_ok_and_honors_explicit_size_over_aseprite_declared_tile_grid() {
+        setup();
+
+        // Same 4.aseprite fixture as above, but with an explicit --size 8 that must win over
+        // the file's own 16x16 tileset grid.
+        let output_name = "aseprite-explicit-size-output.png";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: Some(8),
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: false,
+            file: String::from("4.aseprite"),
+        };
+
+        assert!(run(&args).is_ok());
+        assert_eq!(open(output_name).unwrap().width() % 8, 0);
+
+        assert!(remove_file(output_name).is_ok());
+    }
+
+    #[test]
+    fn returns_ok_and_reads_tiles_and_tilemap_via_passthrough() {
+        setup();
+
+        // 5.aseprite has a tileset and a tilemap layer, so --passthrough-tileset should read
+        // both directly instead of re-slicing the composited frame.
+        let output_name = "aseprite-passthrough-output.png";
+        let map_name = "aseprite-passthrough-tilemap.json";
+        let args = Args {
+            output: Some(String::from(output_name)),
+            size: None,
+            map: Some(String::from(map_name)),
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: true,
+            file: String::from("5.aseprite"),
+        };
+
+        assert!(run(&args).is_ok());
+
+        let map_contents = std::fs::read_to_string(map_name).unwrap();
+        assert!(map_contents.contains("\"tiles\""));
+
+        assert!(remove_file(output_name).is_ok());
+        assert!(remove_file(map_name).is_ok());
+    }
+
+    #[test]
+    fn returns_err_when_passthrough_is_requested_but_file_has_no_tileset() {
+        setup();
+
+        // 6.aseprite has no tileset at all, so --passthrough-tileset has nothing to read.
+        let args = Args {
+            output: None,
+            size: None,
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: None,
+            indexed: false,
+            passthrough_tileset: true,
+            file: String::from("6.aseprite"),
+        };
+
+        assert!(run(&args).is_err());
+    }
+
+    #[test]
+    fn returns_err_when_passthrough_is_combined_with_palette_or_dedupe_flips() {
+        setup();
+
+        let args = Args {
+            output: None,
+            size: None,
+            map: None,
+            dedupe_flips: false,
+            mode: Mode::Single,
+            palette: Some(4),
+            indexed: false,
+            passthrough_tileset: true,
+            file: String::from("5.aseprite"),
+        };
+
+        assert!(run(&args).is_err());
+
+        let args = Args {
+            dedupe_flips: true,
+            palette: None,
+            ..args
+        };
+
+        assert!(run(&args).is_err());
+    }
 }